@@ -1,6 +1,7 @@
-use rand::Rng;
+use rand::seq::SliceRandom;
 use std::{
-    collections::VecDeque, io, io::Stdout, io::Write, thread, time::Duration,
+    collections::HashSet, collections::VecDeque, io, io::Stdout, io::Write,
+    thread, time::Duration,
 };
 use termion::{
     event::Key, input::Keys, input::TermRead, raw::IntoRawMode,
@@ -18,7 +19,7 @@ type CellPos = (usize, usize);
 type Grid = Vec<Vec<Cell>>;
 type IsGameValid = bool;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 enum SnakeDirection {
     Up,
     Right,
@@ -26,6 +27,18 @@ enum SnakeDirection {
     Left,
 }
 
+impl SnakeDirection {
+    /// The direction directly opposite to this one, e.g. `Up` <-> `Down`.
+    fn opposite(&self) -> SnakeDirection {
+        match self {
+            SnakeDirection::Up => SnakeDirection::Down,
+            SnakeDirection::Down => SnakeDirection::Up,
+            SnakeDirection::Left => SnakeDirection::Right,
+            SnakeDirection::Right => SnakeDirection::Left,
+        }
+    }
+}
+
 enum UserInput {
     Quit,
     Direction(SnakeDirection),
@@ -42,6 +55,35 @@ struct GameState {
     // The head queues its positions, the tail pop positions. We can use this
     // to calculate the next tail position.
     head_directions: VecDeque<SnakeDirection>,
+    // When enabled, a head leaving the grid re-enters from the opposite edge
+    // instead of ending the game.
+    wrap_around: bool,
+    // Cells changed since the last render, so `refresh_screen` only has to
+    // repaint those instead of the whole grid.
+    dirty_cells: Vec<CellPos>,
+    // Forces the next `refresh_screen` call to redraw everything, which is
+    // needed for the very first frame.
+    needs_full_redraw: bool,
+    // The length (in bytes) of the status message last rendered, so a change
+    // in length (which could leave stale characters behind) also triggers a
+    // full redraw.
+    last_message_len: usize,
+    // One point per food pellet eaten so far, plus, in the timed-food mode,
+    // the whole seconds left on the countdown when each pellet was eaten.
+    score: u32,
+    // The cells currently occupied by the snake's body, kept in sync with
+    // `grid` so `spawn_food` can cheaply test for occupancy.
+    snake_cells: HashSet<CellPos>,
+    // Set once the board has no empty cell left to spawn food into.
+    has_won: bool,
+    // When enabled, each pellet must be reached before `remaining` runs out.
+    timed_food_enabled: bool,
+    // Time left to reach the current pellet.
+    remaining: Duration,
+    // The countdown `remaining` is reset to whenever a pellet is eaten. It
+    // shrinks slightly with every pellet eaten, down to `MIN_FOOD_ALLOWANCE`,
+    // making later pellets more urgent.
+    per_food_bonus: Duration,
 }
 
 const GRID_ROWS: usize = 15;
@@ -50,24 +92,41 @@ const SNAKE: Cell = 1;
 const EMPTY: Cell = 0;
 const FOOD: Cell = 2;
 const QUIT_CHAR: char = 'q';
-const MAX_FOOD_AMOUNT: usize = 15;
+const WRAP_AROUND_ENABLED: bool = false;
+// The game speeds up as the score rises: after every `SPEEDUP_STEP` points
+// `BASE_TIMING` is divided by one more (1/2, 1/3, ...), never below
+// `MIN_TIMING`.
+const BASE_TIMING: Duration = Duration::from_millis(1000);
+const MIN_TIMING: Duration = Duration::from_millis(200);
+const SPEEDUP_STEP: u32 = 3;
+const TIMED_FOOD_ENABLED: bool = false;
+const BASE_FOOD_ALLOWANCE: Duration = Duration::from_secs(5);
+const MIN_FOOD_ALLOWANCE: Duration = Duration::from_secs(1);
+const FOOD_ALLOWANCE_SHRINK: Duration = Duration::from_millis(100);
 
 fn grid_size(grid: &Grid) -> (usize, usize) {
     (grid.len(), grid[0].len())
 }
 
-fn add_food(grid: &mut Grid, max_amount: usize) {
-    let (nrows, ncols) = grid_size(grid);
-    // A random number generator.
-    let mut rng = rand::thread_rng();
-    // This is a sequence of random food locations like [(x1, y1), (x2, y2)].
-    let food_locations = (0..max_amount)
-        .map(|_| (rng.gen_range(0..nrows), rng.gen_range(0..ncols)));
-    // We add the food to the grid, but only in empty cells.
-    for (food_x, food_y) in food_locations {
-        if grid[food_x][food_y] == EMPTY {
-            grid[food_x][food_y] = FOOD;
+/// Spawns a single food pellet on a uniformly random empty cell, i.e. a cell
+/// that is neither part of the snake nor already holding food. Returns
+/// `false` if no empty cell is left, meaning the player has won.
+fn spawn_food(state: &mut GameState) -> bool {
+    let (nrows, ncols) = grid_size(&state.grid);
+    let empty_cells: Vec<CellPos> = (0..nrows)
+        .flat_map(|x| (0..ncols).map(move |y| (x, y)))
+        .filter(|pos| {
+            !state.snake_cells.contains(pos)
+                && state.grid[pos.0][pos.1] != FOOD
+        })
+        .collect();
+    match empty_cells.choose(&mut rand::thread_rng()) {
+        Some(&pos) => {
+            state.grid[pos.0][pos.1] = FOOD;
+            state.dirty_cells.push(pos);
+            true
         }
+        None => false,
     }
 }
 
@@ -80,14 +139,39 @@ fn init_game_state(nrows: usize, ncols: usize) -> GameState {
         head: (0, 1),
         tail: (0, 0),
         head_directions: VecDeque::from([init_direction()]),
+        wrap_around: WRAP_AROUND_ENABLED,
+        dirty_cells: Vec::new(),
+        needs_full_redraw: true,
+        last_message_len: 0,
+        score: 0,
+        snake_cells: HashSet::new(),
+        has_won: false,
+        timed_food_enabled: TIMED_FOOD_ENABLED,
+        remaining: BASE_FOOD_ALLOWANCE,
+        per_food_bonus: BASE_FOOD_ALLOWANCE,
+    };
+    let add_snake_cell = |game_state: &mut GameState, p: CellPos| {
+        game_state.grid[p.0][p.1] = SNAKE;
+        game_state.snake_cells.insert(p);
     };
-    let mut add_snake_cell = |p: CellPos| game_state.grid[p.0][p.1] = SNAKE;
     // Adding the snake in the grid.
-    add_snake_cell(game_state.head);
-    add_snake_cell(game_state.tail);
+    let (head, tail) = (game_state.head, game_state.tail);
+    add_snake_cell(&mut game_state, head);
+    add_snake_cell(&mut game_state, tail);
     game_state
 }
 
+/// The glyph used to represent a single cell's content.
+fn glyph_for_cell(cell: Cell) -> &'static str {
+    if cell == SNAKE {
+        "▮"
+    } else if cell == FOOD {
+        "✸"
+    } else {
+        " "
+    }
+}
+
 fn print_grid(grid: &Grid) {
     // We sum 2 to consider the vertical lines of each side of the grid.
     let ncols = grid_size(grid).1 + 2;
@@ -102,16 +186,7 @@ fn print_grid(grid: &Grid) {
         put_cursor_left();
         print!("|");
         for cell in row {
-            print!(
-                "{}",
-                if *cell == SNAKE {
-                    "▮"
-                } else if *cell == FOOD {
-                    "✸"
-                } else {
-                    " "
-                }
-            );
+            print!("{}", glyph_for_cell(*cell));
         }
         print!("|\n");
     }
@@ -122,15 +197,17 @@ fn print_grid(grid: &Grid) {
     }
 }
 
-/// Clears the terminal, prints a message and the game grid.
-///
-/// TODO: This implementation is far from efficient since it refreshes the entire
-/// board at each iteration.
-fn refresh_screen(
-    stdout: &mut RawTerminal<Stdout>,
-    message: &String,
-    grid: &Grid,
-) {
+/// The terminal's 1-indexed (column, row) of a grid cell, accounting for the
+/// status message line above the grid and the grid's own top/left border.
+fn _cell_terminal_pos(pos: CellPos) -> (u16, u16) {
+    (pos.1 as u16 + 2, pos.0 as u16 + 3)
+}
+
+/// Clears the terminal and prints the status message and the full game grid.
+/// Used for the first frame and whenever the status message's length
+/// changes, since a shorter message could otherwise leave stale characters
+/// behind.
+fn full_redraw(stdout: &mut RawTerminal<Stdout>, message: &str, grid: &Grid) {
     write!(
         stdout,
         "{}{}{message}\n{}{}",
@@ -140,8 +217,47 @@ fn refresh_screen(
         termion::cursor::Hide,
     )
     .unwrap();
-    print_grid(&grid);
+    print_grid(grid);
+    stdout.flush().unwrap();
+}
+
+/// Repaints only the given cells, each with a single `cursor::Goto` plus its
+/// glyph, instead of redrawing the whole board.
+fn render_dirty_cells(
+    stdout: &mut RawTerminal<Stdout>,
+    grid: &Grid,
+    dirty_cells: &[CellPos],
+) {
+    for &pos in dirty_cells {
+        let (col, row) = _cell_terminal_pos(pos);
+        write!(
+            stdout,
+            "{}{}",
+            termion::cursor::Goto(col, row),
+            glyph_for_cell(grid[pos.0][pos.1])
+        )
+        .unwrap();
+    }
+}
+
+/// Prints the status message and repaints the cells that changed since the
+/// last call. Falls back to [`full_redraw`] for the first frame and whenever
+/// the message's length changes.
+fn refresh_screen(
+    stdout: &mut RawTerminal<Stdout>,
+    message: &str,
+    game: &mut GameState,
+) {
+    if game.needs_full_redraw || message.len() != game.last_message_len {
+        full_redraw(stdout, message, &game.grid);
+        game.needs_full_redraw = false;
+    } else {
+        write!(stdout, "{}{message}", termion::cursor::Goto(1, 1)).unwrap();
+        render_dirty_cells(stdout, &game.grid, &game.dirty_cells);
+    }
     stdout.flush().unwrap();
+    game.last_message_len = message.len();
+    game.dirty_cells.clear();
 }
 
 fn _calc_position(pos: CellPos, direction: SnakeDirection) -> (i16, i16) {
@@ -154,17 +270,26 @@ fn _calc_position(pos: CellPos, direction: SnakeDirection) -> (i16, i16) {
     }
 }
 
+/// Computes where the tail would move to on the next update, without
+/// mutating the state. Used by `_update_snake_tail` to perform the move.
+fn _next_tail_position(state: &GameState) -> CellPos {
+    let (nrows, ncols) = grid_size(&state.grid);
+    let direction = *state.head_directions.back().unwrap();
+    let (mut tx, mut ty) = _calc_position(state.tail, direction);
+    if state.wrap_around {
+        (tx, ty) = (tx.rem_euclid(nrows as i16), ty.rem_euclid(ncols as i16));
+    }
+    (tx as usize, ty as usize)
+}
+
 /// Updates the tail position by making its current cell empty and setting the
 /// new tail position.
 fn _update_snake_tail(state: &mut GameState) {
     state.grid[state.tail.0][state.tail.1] = EMPTY;
-    state.tail = {
-        let (tx, ty) = _calc_position(
-            state.tail,
-            state.head_directions.pop_back().unwrap(),
-        );
-        (tx as usize, ty as usize)
-    };
+    state.snake_cells.remove(&state.tail);
+    state.dirty_cells.push(state.tail);
+    state.tail = _next_tail_position(state);
+    state.head_directions.pop_back();
 }
 
 fn _update_snake_head(
@@ -176,41 +301,106 @@ fn _update_snake_head(
     state.head = (new_head_x as usize, new_head_y as usize);
     // Before updating the cell content with the snake's head, we store whas
     state.grid[state.head.0][state.head.1] = SNAKE;
+    state.snake_cells.insert(state.head);
+    state.dirty_cells.push(state.head);
     state.head_directions.push_front(new_direction);
 }
 
+/// The sleep time between updates for a given score: it shrinks towards
+/// `MIN_TIMING` as the score rises, every `SPEEDUP_STEP` points.
+fn _timing_for_score(score: u32) -> Duration {
+    let divisor = 1 + score / SPEEDUP_STEP;
+    let millis = BASE_TIMING.as_millis() as u32 / divisor;
+    Duration::from_millis(millis.max(MIN_TIMING.as_millis() as u32) as u64)
+}
+
 fn update_snake(
     state: &mut GameState,
     new_direction: SnakeDirection,
 ) -> IsGameValid {
+    if state.timed_food_enabled {
+        state.remaining = state.remaining.saturating_sub(state.timing);
+        if state.remaining.is_zero() {
+            // The countdown to reach the current pellet ran out.
+            return false;
+        }
+    }
     let (nrows, ncols) = grid_size(&state.grid);
-    let (new_head_x, new_head_y) = _calc_position(state.head, new_direction);
-    // If the snake's head position goes out of the board boundaries, the game
-    // is over.
-    if (new_head_x < 0)
+    let (mut new_head_x, mut new_head_y) =
+        _calc_position(state.head, new_direction);
+    if state.wrap_around {
+        // The head re-enters from the opposite edge instead of dying.
+        new_head_x = new_head_x.rem_euclid(nrows as i16);
+        new_head_y = new_head_y.rem_euclid(ncols as i16);
+    } else if (new_head_x < 0)
         || (new_head_y < 0)
         || (new_head_x >= nrows as i16)
         || (new_head_y >= ncols as i16)
     {
+        // If the snake's head position goes out of the board boundaries, the
+        // game is over.
         return false;
     }
     // We know now that the new position is not out of the game grid. We can
     // safely convert their types.
     let (new_head_x, new_head_y) = (new_head_x as usize, new_head_y as usize);
     let does_head_meets_food = state.grid[new_head_x][new_head_y] == FOOD;
-    // We update the head's position and its representation in the grid.
-    _update_snake_head(state, new_head_x, new_head_y, new_direction);
-    // We update the tail's position only if the head does not meet food. If it
-    // does, we want to make the snake grow.
+    // The head biting a SNAKE cell ends the game, unless that cell is the
+    // tail's current spot, which the tail is about to vacate this turn (this
+    // only happens when the snake does not grow).
+    let head_bites_itself = state.grid[new_head_x][new_head_y] == SNAKE
+        && (does_head_meets_food || (new_head_x, new_head_y) != state.tail);
+    if head_bites_itself {
+        return false;
+    }
+    // We update the tail's position only if the head does not meet food. If
+    // it does, we want to make the snake grow. This must run before the head
+    // update below: when the head steps into the cell the tail is vacating,
+    // vacating it after the head would write SNAKE then immediately erase it
+    // as EMPTY.
     if !does_head_meets_food {
         _update_snake_tail(state);
     }
+    // We update the head's position and its representation in the grid.
+    _update_snake_head(state, new_head_x, new_head_y, new_direction);
+    if does_head_meets_food {
+        // The snake grows and the game speeds up.
+        state.score += 1;
+        state.timing = _timing_for_score(state.score);
+        if state.timed_food_enabled {
+            // Reaching the pellet early is rewarded with the leftover
+            // countdown, converted to score.
+            state.score += state.remaining.as_secs() as u32;
+            state.per_food_bonus = state
+                .per_food_bonus
+                .saturating_sub(FOOD_ALLOWANCE_SHRINK)
+                .max(MIN_FOOD_ALLOWANCE);
+            state.remaining = state.per_food_bonus;
+        }
+        // Replenish the eaten pellet; if there is nowhere left to put it, the
+        // board is entirely full of snake and the player has won.
+        if !spawn_food(state) {
+            state.has_won = true;
+            state.last_direction = new_direction;
+            return false;
+        }
+    }
     // We keep track of the last direction.
     state.last_direction = new_direction;
 
     true
 }
 
+/// Builds the status line shown above the grid: the current phase, the
+/// score, and the food countdown when the timed-food mode is enabled.
+fn _status_message(game: &GameState, phase: &str) -> String {
+    let mut message = format!("{phase} | Score: {}", game.score);
+    if game.timed_food_enabled {
+        message.push_str(&format!(" | Food in: {}s", game.remaining.as_secs()));
+    }
+    message
+}
+
 fn capture_input(stdin_keys: &mut Keys<AsyncReader>) -> Option<UserInput> {
     let mut last_key = None;
     // We consider only the user's last input, except if it is to quit the game.
@@ -237,9 +427,9 @@ fn main() -> io::Result<()> {
     let mut stdout = io::stdout().into_raw_mode().unwrap();
     let mut stdin_keys = termion::async_stdin().keys();
     let mut game = init_game_state(GRID_ROWS, GRID_COLUMNS);
-    add_food(&mut game.grid, MAX_FOOD_AMOUNT);
+    spawn_food(&mut game);
 
-    refresh_screen(&mut stdout, &String::from("Start"), &game.grid);
+    refresh_screen(&mut stdout, "Start", &mut game);
     for i in 0.. {
         // We take the user input (if it exists) and check if the user wants to
         // quit the game.
@@ -248,9 +438,15 @@ fn main() -> io::Result<()> {
             break;
         }
         // If the user inputted a new snake direction, we use it; otherwise, we
-        // make the snake continue in the same direction.
+        // make the snake continue in the same direction. A 180-degree
+        // reversal is ignored since it would drive the head straight into
+        // the snake's own neck.
         let new_direction = match user_input {
-            Some(UserInput::Direction(snake_direction)) => snake_direction,
+            Some(UserInput::Direction(snake_direction))
+                if snake_direction != game.last_direction.opposite() =>
+            {
+                snake_direction
+            }
             _ => game.last_direction,
         };
         // We update the snake's position and check if it is valid (the snake
@@ -258,11 +454,15 @@ fn main() -> io::Result<()> {
         let is_valid = update_snake(&mut game, new_direction);
         // If the update is valid, we continue playing.
         if is_valid {
-            refresh_screen(&mut stdout, &format!("Iteration {i}"), &game.grid);
+            let message = _status_message(&game, &format!("Iteration {i}"));
+            refresh_screen(&mut stdout, &message, &mut game);
             thread::sleep(game.timing);
         } else {
-            // If the game is in a invalid state, the game is over.
-            refresh_screen(&mut stdout, &format!("Game is over"), &game.grid);
+            // If the game is in a invalid state, the game is over, unless the
+            // board filled up with snake, in which case the player has won.
+            let end_message = if game.has_won { "You win" } else { "Game is over" };
+            let message = _status_message(&game, end_message);
+            refresh_screen(&mut stdout, &message, &mut game);
             thread::sleep(Duration::from_secs(3));
             break;
         }
@@ -277,16 +477,30 @@ mod main_test {
     use super::*;
 
     #[test]
-    fn test_food_gen() {
-        let (nrows, ncols, nfood) = (10, 10, 5);
+    fn test_spawn_food_avoids_snake() {
+        let (nrows, ncols) = (10, 10);
         let mut game = init_game_state(nrows, ncols);
-        add_food(&mut game.grid, nfood);
+
+        let spawned = spawn_food(&mut game);
         print_grid(&game.grid);
 
-        let grid_sum: u8 = game.grid.iter().flat_map(|cols| cols.iter()).sum();
-        // The food is randomly placed in the grid, and it might be located in
-        // the snake place (therefore, ignored). The number of food in the grid
-        // may discount the initial snake size (which is 2).
-        assert!(grid_sum > (2 * SNAKE + (nfood as u8 - 2) * FOOD))
+        assert!(spawned);
+        // Exactly one pellet should be on the board, and never on a cell the
+        // snake occupies.
+        let food_cells: Vec<CellPos> = (0..nrows)
+            .flat_map(|x| (0..ncols).map(move |y| (x, y)))
+            .filter(|&(x, y)| game.grid[x][y] == FOOD)
+            .collect();
+        assert_eq!(food_cells.len(), 1);
+        assert!(!game.snake_cells.contains(&food_cells[0]));
+    }
+
+    #[test]
+    fn test_spawn_food_reports_win_when_board_is_full() {
+        let (nrows, ncols) = (1, 2);
+        let mut game = init_game_state(nrows, ncols);
+
+        // The snake already fills the only two cells of the board.
+        assert!(!spawn_food(&mut game));
     }
 }